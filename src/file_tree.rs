@@ -1,10 +1,501 @@
+use std::collections::HashMap;
 use std::env::temp_dir;
-use std::fs;
-use std::io::Result;
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use uuid::Uuid;
 
-use tempdir::TempDir;
+/// Directory-structure and file-content operations `FileTree` needs in
+/// order to allocate and populate slots, abstracted so the same
+/// slot-allocation algorithm can run against an in-memory fake for fast,
+/// isolated tests, or against an alternate store, instead of always going
+/// through `std::fs`.
+///
+/// This covers every operation `FileTree` performs, including creating
+/// and tearing down its own root and streaming content into a slot (see
+/// [`FileTree::write_new_file_with`]).
+pub trait FsBackend: fmt::Debug {
+    /// Create `path` and any missing parent directories.
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Return whether `path` exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Atomically rename/move `from` to `to`.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// List the immediate entries of `dir` as `(name, is_dir)` pairs. A
+    /// missing or unreadable directory yields an empty list rather than
+    /// an error, matching the best-effort walk callers already expect.
+    fn read_dir_names(&self, dir: &Path) -> Vec<(String, bool)>;
+
+    /// Remove the file at `path`, if present.
+    fn remove_file(&self, path: &Path) -> Result<()>;
+
+    /// Recursively remove `path` and everything under it, if present.
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Copy the file at `src` to `dest`, overwriting `dest` if it already
+    /// exists.
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()>;
+
+    /// Create (or truncate) the file at `path` and open it for writing,
+    /// returning a handle content can be streamed into.
+    ///
+    /// This is the hook [`FileTree::write_new_file_with`] streams a new
+    /// slot's contents through, so a fake backend never touches the real
+    /// filesystem even for the write step.
+    fn create_file(&self, path: &Path) -> Result<Box<dyn FsWriteFile>>;
+}
+
+/// A writable, syncable file handle, as returned by [`FsBackend::create_file`].
+///
+/// `std::fs::File` is the concrete handle [`StdFsBackend`] returns; fake
+/// backends can return anything that buffers bytes and no-ops `sync_all`.
+pub trait FsWriteFile: Write {
+    /// Flush and fsync the handle so its contents are durable.
+    fn sync_all(&self) -> Result<()>;
+}
+
+impl FsWriteFile for File {
+    fn sync_all(&self) -> Result<()> {
+        File::sync_all(self)
+    }
+}
+
+/// The default [`FsBackend`], backed directly by `std::fs`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFsBackend;
+
+impl FsBackend for StdFsBackend {
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn read_dir_names(&self, dir: &Path) -> Vec<(String, bool)> {
+        match fs::read_dir(dir) {
+            Ok(read_dir) => read_dir
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let is_dir = e.file_type().ok()?.is_dir();
+                    Some((e.file_name().to_string_lossy().into_owned(), is_dir))
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()> {
+        fs::copy(src, dest).map(|_| ())
+    }
+
+    fn create_file(&self, path: &Path) -> Result<Box<dyn FsWriteFile>> {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+/// Builder for configuring a [`FileTree`]'s fan-out, depth, and filename
+/// format before it is constructed.
+///
+/// Configure the knobs you care about, then call `build()` or `build_in()`
+/// to get a `FileTree`. Leaving everything at its default reproduces the
+/// historical, hard-coded layout (1,000 entries per directory, 3 levels
+/// deep, no prefix/suffix).
+///
+/// # Examples
+///
+/// ```
+/// use file_tree::FileTreeBuilder;
+///
+/// let file_tree = FileTreeBuilder::new()
+///     .fanout(1000)
+///     .depth(3)
+///     .prefix("data_")
+///     .suffix(".bin")
+///     .build(false)
+///     .unwrap();
+/// assert!(file_tree.get_root().exists());
+/// ```
+pub struct FileTreeBuilder {
+    fanout: u64,
+    depth: u32,
+    prefix: String,
+    suffix: String,
+}
+
+impl Default for FileTreeBuilder {
+    fn default() -> Self {
+        FileTreeBuilder {
+            fanout: 1000,
+            depth: 3,
+            prefix: String::new(),
+            suffix: String::new(),
+        }
+    }
+}
+
+impl FileTreeBuilder {
+    /// Start a new builder with the default layout: 1,000 entries per
+    /// directory, 3 levels deep, and no filename prefix/suffix.
+    pub fn new() -> FileTreeBuilder {
+        FileTreeBuilder::default()
+    }
+
+    /// Set the number of entries allowed per directory. Must be at least 2;
+    /// rejected at build time otherwise.
+    ///
+    /// This also controls how many digits are used per path segment, since
+    /// a segment must have enough digits to address `fanout` distinct
+    /// entries.
+    pub fn fanout(mut self, fanout: u64) -> FileTreeBuilder {
+        self.fanout = fanout;
+        self
+    }
+
+    /// Set the number of nested directory levels used to spread out
+    /// entries before they land in a leaf directory.
+    pub fn depth(mut self, depth: u32) -> FileTreeBuilder {
+        self.depth = depth;
+        self
+    }
+
+    /// Set a prefix prepended to every generated file name.
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> FileTreeBuilder {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Set a suffix appended to every generated file name (e.g. a file
+    /// extension such as `.bin`).
+    pub fn suffix<S: Into<String>>(mut self, suffix: S) -> FileTreeBuilder {
+        self.suffix = suffix.into();
+        self
+    }
+
+    fn layout(&self) -> Result<Layout> {
+        if self.fanout < 2 {
+            return Err(Error::other(format!(
+                "fanout must be at least 2, got {}",
+                self.fanout
+            )));
+        }
+        Ok(Layout::new(
+            self.fanout,
+            self.depth,
+            self.prefix.clone(),
+            self.suffix.clone(),
+        ))
+    }
+
+    /// Build a new `FileTree` using this builder's layout. If `persistent`
+    /// is `false` the directory and all its contents will be deleted when
+    /// the returned `FileTree` is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fanout` is less than 2. If `persistent` is
+    /// `false`, the ephemeral root will be created through the backend,
+    /// and any related errors will be returned here.
+    pub fn build(self, persistent: bool) -> Result<FileTree> {
+        self.build_with_backend(Arc::new(StdFsBackend), persistent)
+    }
+
+    /// Build a new `FileTree` rooted under `path` using this builder's
+    /// layout. If `persistent` is `false` the directory and all its
+    /// contents will be deleted when the returned `FileTree` is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fanout` is less than 2. If `persistent` is
+    /// `false`, the ephemeral root will be created through the backend,
+    /// and any related errors will be returned here.
+    pub fn build_in(self, path: PathBuf, persistent: bool) -> Result<FileTree> {
+        self.build_in_with_backend(Arc::new(StdFsBackend), path, persistent)
+    }
+
+    /// Like [`FileTreeBuilder::build`], but runs all directory-structure
+    /// operations through `backend` instead of `std::fs`. See
+    /// [`FsBackend`].
+    ///
+    /// # Errors
+    ///
+    /// See [`FileTreeBuilder::build`].
+    pub fn build_with_backend(
+        self,
+        backend: Arc<dyn FsBackend>,
+        persistent: bool,
+    ) -> Result<FileTree> {
+        if persistent {
+            let root = temp_dir().join(format!("file_tree-{}", Uuid::new_v4().hyphenated()));
+            self.build_in_with_backend(backend, root, persistent)
+        } else {
+            self.build_in_with_backend(backend, temp_dir(), persistent)
+        }
+    }
+
+    /// Creates a `FileTree` from an existing directory structure laid out
+    /// with this builder's fanout/depth/prefix/suffix, instead of the
+    /// default layout. `path` should be equivalent to the result of
+    /// calling `get_root()` on the previous (persistent) `FileTree` built
+    /// with the same settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fanout` is less than 2.
+    pub fn from_existing(self, path: PathBuf) -> Result<FileTree> {
+        self.from_existing_with_backend(Arc::new(StdFsBackend), path)
+    }
+
+    /// Like [`FileTreeBuilder::from_existing`], but runs all
+    /// directory-structure operations through `backend` instead of
+    /// `std::fs`. See [`FsBackend`].
+    ///
+    /// # Errors
+    ///
+    /// See [`FileTreeBuilder::from_existing`].
+    pub fn from_existing_with_backend(
+        self,
+        backend: Arc<dyn FsBackend>,
+        path: PathBuf,
+    ) -> Result<FileTree> {
+        let layout = self.layout()?;
+        Ok(FileTree {
+            root: path,
+            persistent: true,
+            counter: 0,
+            layout,
+            backend,
+            moved: false,
+        })
+    }
+
+    /// Creates a `FileTree` from an existing directory structure laid out
+    /// with this builder's fanout/depth/prefix/suffix, resuming the
+    /// counter from the highest slot already in use. See
+    /// [`FileTree::resume_from_existing`] for how the counter is
+    /// reconstructed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `fanout` is less than 2.
+    pub fn resume_from_existing(self, path: PathBuf) -> Result<FileTree> {
+        self.resume_from_existing_with_backend(Arc::new(StdFsBackend), path)
+    }
+
+    /// Like [`FileTreeBuilder::resume_from_existing`], but runs all
+    /// directory-structure operations through `backend` instead of
+    /// `std::fs`. See [`FsBackend`].
+    ///
+    /// # Errors
+    ///
+    /// See [`FileTreeBuilder::resume_from_existing`].
+    pub fn resume_from_existing_with_backend(
+        self,
+        backend: Arc<dyn FsBackend>,
+        path: PathBuf,
+    ) -> Result<FileTree> {
+        let layout = self.layout()?;
+        let counter = FileTree::resume_counter(backend.as_ref(), &path, &layout);
+        Ok(FileTree {
+            root: path,
+            persistent: true,
+            counter,
+            layout,
+            backend,
+            moved: false,
+        })
+    }
+
+    /// Like [`FileTreeBuilder::build_in`], but runs all directory-structure
+    /// operations through `backend` instead of `std::fs`. See
+    /// [`FsBackend`].
+    ///
+    /// If `persistent` is `true`, the tree is rooted exactly at `path`
+    /// (created through `backend` if missing), so that a caller-chosen,
+    /// deterministic location stays reopenable: two calls agreeing on the
+    /// same `path` land on the same tree. If `persistent` is `false`, the
+    /// root is instead a freshly named subdirectory of `path`, created and
+    /// later torn down entirely through `backend`, so a fake backend never
+    /// causes a real directory to be created and callers can safely share
+    /// one `path` (e.g. the system temp directory) across many ephemeral
+    /// trees without colliding.
+    ///
+    /// # Errors
+    ///
+    /// See [`FileTreeBuilder::build_in`].
+    pub fn build_in_with_backend(
+        self,
+        backend: Arc<dyn FsBackend>,
+        path: PathBuf,
+        persistent: bool,
+    ) -> Result<FileTree> {
+        let layout = self.layout()?;
+        let root = if persistent {
+            path
+        } else {
+            path.join(format!("file_tree-{}", Uuid::new_v4().hyphenated()))
+        };
+        backend.create_dir_all(&root)?;
+        Ok(FileTree {
+            root,
+            persistent,
+            counter: 0,
+            layout,
+            backend,
+            moved: false,
+        })
+    }
+}
+
+/// Derived layout parameters shared by path computation and slot capacity
+/// checks. Computed once by the builder and cloned into `FileTree` and its
+/// iterator, so the digit-width math lives in one place.
+#[derive(Clone)]
+struct Layout {
+    fanout: u64,
+    depth: u32,
+    segment_width: usize,
+    prefix: String,
+    suffix: String,
+}
+
+impl Layout {
+    fn new(fanout: u64, depth: u32, prefix: String, suffix: String) -> Layout {
+        let segment_width = if fanout <= 1 {
+            1
+        } else {
+            ((fanout - 1) as f64).log10().floor() as usize + 1
+        };
+        Layout {
+            fanout,
+            depth,
+            segment_width,
+            prefix,
+            suffix,
+        }
+    }
+
+    /// Total number of slots this layout can address before `counter`
+    /// would have to grow past the digits reserved for it.
+    fn capacity(&self) -> u64 {
+        self.fanout.saturating_pow(self.depth.saturating_add(1))
+    }
+
+    fn total_digits(&self) -> usize {
+        self.segment_width * (self.depth as usize + 1)
+    }
+
+    /// Strip this layout's prefix/suffix from a file name, returning the
+    /// remaining digit string, or `None` if the name doesn't carry both.
+    fn strip_affixes<'a>(&self, name: &'a str) -> Option<&'a str> {
+        name.strip_prefix(self.prefix.as_str())
+            .and_then(|rest| rest.strip_suffix(self.suffix.as_str()))
+    }
+
+    /// List the segment subdirectories of `dir` in ascending (and thus
+    /// counter) order. An unreadable or missing `dir` yields an empty
+    /// list rather than an error, since callers use this for a lazy,
+    /// best-effort walk.
+    fn list_subdir_names(&self, backend: &dyn FsBackend, dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = backend
+            .read_dir_names(dir)
+            .into_iter()
+            .filter(|(_, is_dir)| *is_dir)
+            .map(|(name, _)| name)
+            .filter(|n| n.len() == self.segment_width && n.chars().all(|c| c.is_ascii_digit()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// List the file names in a leaf directory that carry this layout's
+    /// prefix/suffix around a full-width digit string, in ascending (and
+    /// thus counter) order. The names are returned whole, affixes
+    /// included. An unreadable or missing `dir` yields an empty list
+    /// rather than an error.
+    fn list_leaf_file_names(&self, backend: &dyn FsBackend, dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = backend
+            .read_dir_names(dir)
+            .into_iter()
+            .filter(|(_, is_dir)| !is_dir)
+            .map(|(name, _)| name)
+            .filter(|n| {
+                self.strip_affixes(n).is_some_and(|digits| {
+                    digits.len() == self.total_digits()
+                        && digits.chars().all(|c| c.is_ascii_digit())
+                })
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Split `counter` into `depth + 1` base-`fanout` digits, most
+    /// significant first, each zero-padded to `segment_width` decimal
+    /// characters: the first `depth` become directory components, all of
+    /// them concatenated (prefixed/suffixed) become the file name.
+    fn digits_for(&self, counter: u64) -> Vec<String> {
+        let radix = self.fanout.max(1);
+        let mut values = vec![0u64; self.depth as usize + 1];
+        let mut remaining = counter;
+        for value in values.iter_mut().rev() {
+            *value = remaining % radix;
+            remaining /= radix;
+        }
+        values
+            .into_iter()
+            .map(|v| format!("{:0width$}", v, width = self.segment_width))
+            .collect()
+    }
+
+    /// Inverse of [`Layout::digits_for`]: reassemble a counter from a
+    /// string of `total_digits()` decimal characters, interpreted as
+    /// `depth + 1` base-`fanout` digits. Returns `None` if `digits` isn't
+    /// exactly the right length or a segment doesn't parse.
+    fn decode_digits(&self, digits: &str) -> Option<u64> {
+        if digits.len() != self.total_digits() {
+            return None;
+        }
+        let radix = self.fanout.max(1);
+        let mut counter = 0u64;
+        for chunk in digits.as_bytes().chunks(self.segment_width) {
+            let value: u64 = std::str::from_utf8(chunk).ok()?.parse().ok()?;
+            counter = counter.checked_mul(radix)?.checked_add(value)?;
+        }
+        Some(counter)
+    }
+
+    fn path_for(&self, root: &Path, counter: u64) -> PathBuf {
+        let segments = self.digits_for(counter);
+
+        let mut dir = root.to_path_buf();
+        for segment in &segments[0..self.depth as usize] {
+            dir.push(segment);
+        }
+
+        let digits = segments.concat();
+        let file_name = format!("{}{}{}", self.prefix, digits, self.suffix);
+        dir.join(file_name)
+    }
+}
 
 /// Creates a directory structure suitable for storing large numbers of files.
 /// Optionally deletes the created directory and files when dropped.
@@ -12,10 +503,29 @@ use tempdir::TempDir;
 /// Slots for new files are allocated using `get_new_file()`. This struct will
 /// create new subdirectories as needed to ensure that no subdirectory contains
 /// more than 1,000 files/subdirectories.
+///
+/// Use [`FileTreeBuilder`] instead of the `new*` constructors to customize
+/// the fan-out, depth, or filename format.
 pub struct FileTree {
-    tmp_dir: Option<TempDir>,
-    persistent_dir: Option<PathBuf>,
+    root: PathBuf,
+    persistent: bool,
     counter: u64,
+    layout: Layout,
+    backend: Arc<dyn FsBackend>,
+    /// Set by [`FileTree::persist`] once this tree's directory has been
+    /// moved out from under it, so `Drop` doesn't also try to remove it.
+    /// Unlike `std::mem::forget`-ing the whole struct, this only
+    /// suppresses the directory removal: `backend` and every other field
+    /// still drop normally.
+    moved: bool,
+}
+
+impl Drop for FileTree {
+    fn drop(&mut self) {
+        if !self.persistent && !self.moved {
+            let _ = self.backend.remove_dir_all(&self.root);
+        }
+    }
 }
 
 impl FileTree {
@@ -37,32 +547,20 @@ impl FileTree {
     ///
     /// # Errors
     ///
-    /// If `persistent` is `false`, the directory will be created using
-    /// `tempdir::TempDir`, and any related errors will be returned here
+    /// If `persistent` is `false`, the directory will be created as an
+    /// empty ephemeral root, and any related errors will be returned here
     pub fn new_in(path: PathBuf, persistent: bool) -> Result<FileTree> {
-        if persistent {
-            Ok(FileTree {
-                tmp_dir: None,
-                persistent_dir: Some(path),
-                counter: 0,
-            })
-        } else {
-            Ok(FileTree {
-                tmp_dir: Some(TempDir::new_in(path, "file_tree")?),
-                persistent_dir: None,
-                counter: 0,
-            })
-        }
+        FileTreeBuilder::default().build_in(path, persistent)
     }
 
     /// Create a new directory structure. If `persistent` is `false` the
     /// directory and all it's contents will be deleted when the returned
-    /// `FileTree` is dropped.    
-    /// 
+    /// `FileTree` is dropped.
+    ///
     /// # Examples
-    /// 
+    ///
     /// Create a new temporary data structure and make sure the base path exists
-    /// 
+    ///
     /// ```
     /// use file_tree::FileTree;
     ///
@@ -72,45 +570,56 @@ impl FileTree {
     ///
     /// # Errors
     ///
-    /// If `persistent` is `false`, the directory will be created using
-    /// `tempdir::TempDir`, and any related errors will be returned here
+    /// If `persistent` is `false`, the directory will be created as an
+    /// empty ephemeral root, and any related errors will be returned here
     pub fn new(persistent: bool) -> Result<FileTree> {
-        if persistent {
-            let uuid = Uuid::new_v4().hyphenated().to_string();
+        FileTreeBuilder::default().build(persistent)
+    }
 
-            Ok(FileTree {
-                tmp_dir: None,
-                persistent_dir: Some(temp_dir().join(uuid)),
-                counter: 0,
-            })
-        } else {
-            Ok(FileTree {
-                tmp_dir: Some(TempDir::new("file_tree")?),
-                persistent_dir: None,
-                counter: 0,
-            })
-        }
+    /// Create a new directory structure under `path`, running all
+    /// directory-structure operations through `backend` instead of
+    /// `std::fs`. If `persistent` is `false` the directory and all its
+    /// contents will be deleted when the returned `FileTree` is dropped.
+    ///
+    /// This is the hook for driving the slot-allocation algorithm against
+    /// an in-memory fake in tests, or against an alternate store in
+    /// production. See [`FsBackend`].
+    ///
+    /// # Errors
+    ///
+    /// If `persistent` is `false`, the directory will be created as an
+    /// empty ephemeral root, and any related errors will be returned here.
+    pub fn new_with_backend(
+        backend: Arc<dyn FsBackend>,
+        path: PathBuf,
+        persistent: bool,
+    ) -> Result<FileTree> {
+        FileTreeBuilder::default().build_in_with_backend(backend, path, persistent)
     }
 
     /// Creates a `FileTree` from an existing directory structure. `path` should
     /// be equivalent to the result of calling `get_root()` on the previous
     /// (persistent) `FileTree`.
-    /// 
+    ///
+    /// This assumes the default layout. Use
+    /// [`FileTreeBuilder::from_existing`] instead if the tree was built
+    /// with a custom fanout, depth, prefix, or suffix.
+    ///
     /// # Examples
-    /// 
+    ///
     /// Re-create a `FileTree` using an existing file structure
-    /// 
+    ///
     /// ```
     /// use file_tree::FileTree;
     /// use std::fs::File;
-    /// 
+    ///
     /// // create a `FileTree` with one file
     /// let mut ft = FileTree::new(true).unwrap();
     /// let file_path = ft.get_new_file().unwrap();
     /// File::create(file_path.clone()).unwrap();
     /// let base = ft.get_root();
     /// drop(ft);
-    /// 
+    ///
     /// // create a `FileTree` using the existing path, and make sure that the
     /// // files we pull back don't overwrite the existing one
     /// let mut ft2 = FileTree::from_existing(base);
@@ -119,10 +628,93 @@ impl FileTree {
     /// assert_eq!(file2.file_name().unwrap(), "000000000001");
     /// ```
     pub fn from_existing(path: PathBuf) -> FileTree {
-        FileTree {
-            tmp_dir: None,
-            persistent_dir: Some(path),
-            counter: 0,
+        FileTreeBuilder::default()
+            .from_existing(path)
+            .expect("default builder layout (fanout 1000) is always valid")
+    }
+
+    /// Like [`FileTree::from_existing`], but runs all directory-structure
+    /// operations through `backend` instead of `std::fs`. See
+    /// [`FsBackend`].
+    ///
+    /// This assumes the default layout. Use
+    /// [`FileTreeBuilder::from_existing_with_backend`] instead if the tree
+    /// was built with a custom fanout, depth, prefix, or suffix.
+    pub fn from_existing_with_backend(backend: Arc<dyn FsBackend>, path: PathBuf) -> FileTree {
+        FileTreeBuilder::default()
+            .from_existing_with_backend(backend, path)
+            .expect("default builder layout (fanout 1000) is always valid")
+    }
+
+    /// Creates a `FileTree` from an existing directory structure, resuming
+    /// the counter from the highest slot already in use instead of
+    /// starting over at zero.
+    ///
+    /// Unlike [`FileTree::from_existing`], which lets `get_new_file` skip
+    /// past occupied slots one `exists()` check at a time (an O(n) walk
+    /// from zero), this reconstructs the counter directly: at each
+    /// directory level it picks the lexicographically largest `NNN`
+    /// subdirectory that exists, descends into it, and finally parses the
+    /// largest file name in the deepest leaf directory. This costs one
+    /// directory listing per level rather than one stat per previously
+    /// allocated slot.
+    ///
+    /// This assumes the default layout. Use
+    /// [`FileTreeBuilder::resume_from_existing`] instead if the tree was
+    /// built with a custom fanout, depth, prefix, or suffix.
+    pub fn resume_from_existing(path: PathBuf) -> Result<FileTree> {
+        FileTreeBuilder::default().resume_from_existing(path)
+    }
+
+    /// Like [`FileTree::resume_from_existing`], but runs all
+    /// directory-structure operations through `backend` instead of
+    /// `std::fs`. See [`FsBackend`].
+    ///
+    /// This assumes the default layout. Use
+    /// [`FileTreeBuilder::resume_from_existing_with_backend`] instead if
+    /// the tree was built with a custom fanout, depth, prefix, or suffix.
+    pub fn resume_from_existing_with_backend(
+        backend: Arc<dyn FsBackend>,
+        path: PathBuf,
+    ) -> Result<FileTree> {
+        FileTreeBuilder::default().resume_from_existing_with_backend(backend, path)
+    }
+
+    fn resume_counter(backend: &dyn FsBackend, root: &Path, layout: &Layout) -> u64 {
+        let mut dir = root.to_path_buf();
+        let mut dir_value = 0u64;
+        let radix = layout.fanout.max(1);
+
+        for level in 0..layout.depth {
+            match layout.list_subdir_names(backend, &dir).pop() {
+                Some(segment) => {
+                    let digit: u64 = segment.parse().unwrap_or(0);
+                    dir_value = dir_value.saturating_mul(radix).saturating_add(digit);
+                    dir.push(segment);
+                }
+                None => {
+                    // This level came back empty (e.g. a leaf directory was
+                    // pruned after its files were deleted) — don't discard
+                    // `dir_value` accumulated from the levels found so far.
+                    // Treat every remaining digit position, including this
+                    // one and the leaf file digit, as zero instead.
+                    let remaining_levels = layout.depth - level + 1;
+                    return dir_value.saturating_mul(radix.saturating_pow(remaining_levels));
+                }
+            }
+        }
+
+        let max_file_digits = layout
+            .list_leaf_file_names(backend, &dir)
+            .pop()
+            .and_then(|name| {
+                let digits = layout.strip_affixes(&name)?;
+                layout.decode_digits(digits)
+            });
+
+        match max_file_digits {
+            Some(counter) => counter + 1,
+            None => dir_value.saturating_mul(radix),
         }
     }
 
@@ -138,23 +730,23 @@ impl FileTree {
     /// File paths are generated such that each new leaf directory (starting
     /// with `000/000/000/`) will be filled entirely before creating a new
     /// directory (next would be `000/000/001/`).
-    /// 
-    /// 
+    ///
+    ///
     /// # Examples
-    /// 
+    ///
     /// Retrieve two distinct file paths via `get_new_file()`
-    /// 
+    ///
     /// ```
     /// use file_tree::FileTree;
-    /// 
+    ///
     /// let mut file_tree = FileTree::new(false).unwrap();
-    /// 
+    ///
     /// let writeable_path = file_tree.get_new_file().unwrap();
     /// assert_eq!(
     ///     writeable_path,
     ///     file_tree.get_root().join("000/000/000/000000000000")
     /// );
-    /// 
+    ///
     /// let writeable_path_2 = file_tree.get_new_file().unwrap();
     /// assert_eq!(
     ///     writeable_path_2,
@@ -165,43 +757,334 @@ impl FileTree {
     /// # Errors
     ///
     /// If a new subdirectory is required, `fs::create_dir_all` will be called.
-    /// Any errors from that call will be returned here
+    /// With a custom backend, its `create_dir_all` is called instead. Any
+    /// errors from that call will be returned here. An error is also
+    /// returned if the tree's fanout/depth layout has run out of slots.
     pub fn get_new_file(&mut self) -> Result<PathBuf> {
         let mut new_file = self.get_new_file_uniq()?;
-        while new_file.exists() {
+        while self.backend.exists(&new_file) {
             new_file = self.get_new_file_uniq()?;
         }
         Ok(new_file)
     }
 
     fn get_new_file_uniq(&mut self) -> Result<PathBuf> {
-        let uid = format!("{:012}", self.counter);
+        if self.counter >= self.layout.capacity() {
+            return Err(Error::other(
+                "FileTree has run out of slots for its configured fanout/depth",
+            ));
+        }
+        let path = self.layout.path_for(&self.get_root(), self.counter);
         self.counter += 1;
-        let mut buff = String::with_capacity(3);
-        let mut parts = Vec::with_capacity(4);
-        for c in uid.chars() {
-            if buff.chars().count() >= 3 {
-                parts.push(buff);
-                buff = String::with_capacity(3);
+        let dir = path.parent().unwrap();
+        self.backend.create_dir_all(dir)?;
+        Ok(path)
+    }
+
+    /// Allocate a new slot and atomically write `data` into it.
+    ///
+    /// The data is first written to a temporary file in the same leaf
+    /// directory as the destination slot, then moved into place with the
+    /// backend's `rename` (`fs::rename` for [`StdFsBackend`]). Since rename within one directory is atomic on POSIX
+    /// and Windows, readers never observe a partially written file, and a
+    /// crash mid-write leaves behind only an orphan temp file rather than
+    /// a corrupt slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a new slot could not be allocated, the temp
+    /// file could not be created or written, or the final rename fails.
+    /// The temp file is removed before returning on any write error.
+    pub fn write_new_file(&mut self, data: &[u8]) -> Result<PathBuf> {
+        self.write_new_file_with(|file| file.write_all(data))
+    }
+
+    /// Allocate a new slot and populate it atomically by calling `f` with
+    /// a handle to a temporary file, then renaming that file into place.
+    ///
+    /// The temporary file is created and written through `backend` (see
+    /// [`FsBackend::create_file`]), so a fake backend never needs a real
+    /// filesystem underneath it.
+    ///
+    /// See [`FileTree::write_new_file`] for the atomicity guarantees. Use
+    /// this variant to stream data into the slot instead of buffering it
+    /// into a single `&[u8]` up front.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a new slot could not be allocated, the temp
+    /// file could not be created, `f` returns an error, or the final
+    /// rename fails. The temp file is removed before returning on any
+    /// write error.
+    pub fn write_new_file_with<F>(&mut self, f: F) -> Result<PathBuf>
+    where
+        F: FnOnce(&mut dyn FsWriteFile) -> Result<()>,
+    {
+        let dest = self.get_new_file()?;
+        let tmp_path = dest.with_file_name(format!(
+            ".{}.tmp",
+            Uuid::new_v4().hyphenated()
+        ));
+
+        let mut tmp_file = self.backend.create_file(&tmp_path)?;
+        let result = f(tmp_file.as_mut()).and_then(|_| tmp_file.sync_all());
+        drop(tmp_file);
+
+        match result {
+            Ok(()) => match self.backend.rename(&tmp_path, &dest) {
+                Ok(()) => Ok(dest),
+                Err(e) => {
+                    let _ = self.backend.remove_file(&tmp_path);
+                    Err(e)
+                }
+            },
+            Err(e) => {
+                let _ = self.backend.remove_file(&tmp_path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Return the root path for the file tree
+    pub fn get_root(&self) -> PathBuf {
+        self.root.clone()
+    }
+
+    /// Returns an iterator over every existing file slot, in counter
+    /// order, without materializing the whole list up front.
+    ///
+    /// The tree mirrors the filesystem hierarchy, so this holds a small
+    /// stack of directory-entry cursors (one per level) and descends into
+    /// the lexicographically-ordered segment directories lazily, skipping
+    /// whole missing subtrees rather than probing each candidate counter.
+    pub fn iter(&self) -> impl Iterator<Item = PathBuf> {
+        let layout = self.layout.clone();
+        let backend = self.backend.clone();
+        let root = self.get_root();
+        let is_leaf = layout.depth == 0;
+        let names = if is_leaf {
+            layout.list_leaf_file_names(backend.as_ref(), &root)
+        } else {
+            layout.list_subdir_names(backend.as_ref(), &root)
+        };
+        FileTreeIter {
+            layout,
+            backend,
+            stack: vec![IterFrame {
+                dir: root,
+                level: 0,
+                is_leaf,
+                names: names.into_iter(),
+            }],
+        }
+    }
+
+    /// Move this `FileTree`'s contents to `dest` and return a persistent
+    /// `FileTree` rooted there, preserving the counter.
+    ///
+    /// The directory is moved via the backend's `rename` where possible (a
+    /// single atomic operation on the same filesystem), falling back to a
+    /// recursive copy through the backend when `dest` is on a different
+    /// filesystem. This tree's own directory removal on drop is
+    /// suppressed, since its contents now live at `dest`, owned by the
+    /// returned `FileTree`; every other field of `self` (notably `backend`)
+    /// still drops normally.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dest` already exists and is non-empty, or if
+    /// neither the rename nor the recursive copy fallback succeeds.
+    pub fn persist(mut self, dest: PathBuf) -> Result<FileTree> {
+        let source = self.root.clone();
+
+        if source != dest {
+            if self.backend.exists(&dest) && !self.backend.read_dir_names(&dest).is_empty() {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("persist destination {} is not empty", dest.display()),
+                ));
+            }
+            if let Some(parent) = dest.parent() {
+                self.backend.create_dir_all(parent)?;
+            }
+            match self.backend.rename(&source, &dest) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                    Self::copy_dir_recursive(self.backend.as_ref(), &source, &dest)?;
+                    self.backend.remove_dir_all(&source)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let persisted = FileTree {
+            root: dest,
+            persistent: true,
+            counter: self.counter,
+            layout: self.layout.clone(),
+            backend: self.backend.clone(),
+            moved: false,
+        };
+        // The directory has already been moved or copied to `dest`; mark
+        // `self` as moved so its `Drop` impl skips the directory removal,
+        // instead of forgetting the whole struct (which would also leak
+        // `backend` and every other field).
+        self.moved = true;
+        Ok(persisted)
+    }
+
+    fn copy_dir_recursive(backend: &dyn FsBackend, src: &Path, dest: &Path) -> Result<()> {
+        backend.create_dir_all(dest)?;
+        for (name, is_dir) in backend.read_dir_names(src) {
+            let src_path = src.join(&name);
+            let dest_path = dest.join(&name);
+            if is_dir {
+                Self::copy_dir_recursive(backend, &src_path, &dest_path)?;
+            } else {
+                backend.copy_file(&src_path, &dest_path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct IterFrame {
+    dir: PathBuf,
+    level: u32,
+    is_leaf: bool,
+    names: std::vec::IntoIter<String>,
+}
+
+struct FileTreeIter {
+    layout: Layout,
+    backend: Arc<dyn FsBackend>,
+    stack: Vec<IterFrame>,
+}
+
+impl Iterator for FileTreeIter {
+    type Item = PathBuf;
+
+    fn next(&mut self) -> Option<PathBuf> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            match frame.names.next() {
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+                Some(name) => {
+                    let child = frame.dir.join(&name);
+                    if frame.is_leaf {
+                        return Some(child);
+                    }
+                    let next_level = frame.level + 1;
+                    let is_leaf = next_level == self.layout.depth;
+                    let names = if is_leaf {
+                        self.layout.list_leaf_file_names(self.backend.as_ref(), &child)
+                    } else {
+                        self.layout.list_subdir_names(self.backend.as_ref(), &child)
+                    };
+                    self.stack.push(IterFrame {
+                        dir: child,
+                        level: next_level,
+                        is_leaf,
+                        names: names.into_iter(),
+                    });
+                }
             }
-            buff.push(c);
         }
-        if buff.chars().count() > 0 {
-            parts.push(buff);
+    }
+}
+
+/// A `FileTree` variant that maps string keys to stable file paths:
+/// looking up a key allocates a new slot the first time it's seen, and
+/// returns that same path on every subsequent lookup.
+pub struct KeyedFileTree {
+    file_tree: FileTree,
+    files: HashMap<String, PathBuf>,
+}
+
+impl KeyedFileTree {
+    /// Create a new directory structure under `path`. If `persistent` is
+    /// `false` the directory and all it's contents will be deleted when
+    /// the returned `KeyedFileTree` is dropped.
+    ///
+    /// # Errors
+    ///
+    /// If `persistent` is `false`, the directory will be created as an
+    /// empty ephemeral root, and any related errors will be returned here
+    pub fn new_in(path: PathBuf, persistent: bool) -> Result<KeyedFileTree> {
+        Ok(KeyedFileTree {
+            file_tree: FileTree::new_in(path, persistent)?,
+            files: HashMap::new(),
+        })
+    }
+
+    /// Create a new directory structure. If `persistent` is `false` the
+    /// directory and all it's contents will be deleted when the returned
+    /// `KeyedFileTree` is dropped.
+    ///
+    /// # Errors
+    ///
+    /// If `persistent` is `false`, the directory will be created as an
+    /// empty ephemeral root, and any related errors will be returned here
+    pub fn new(persistent: bool) -> Result<KeyedFileTree> {
+        Ok(KeyedFileTree {
+            file_tree: FileTree::new(persistent)?,
+            files: HashMap::new(),
+        })
+    }
+
+    /// Creates a `KeyedFileTree` from an existing directory structure and
+    /// a previously recorded key → path map (see
+    /// [`KeyedFileTree::get_existing_files`]). `path` should be equivalent
+    /// to the result of calling `get_root()` on the previous (persistent)
+    /// `KeyedFileTree`.
+    pub fn from_existing(path: PathBuf, files: HashMap<String, PathBuf>) -> KeyedFileTree {
+        KeyedFileTree {
+            file_tree: FileTree::from_existing(path),
+            files,
         }
-        let path_str = format!("{0}/{1}/{2}", parts[0], parts[1], parts[2]);
-        let path = self.get_root().join(path_str);
-        match fs::create_dir_all(&path) {
-            Ok(_) => Ok(path.join(uid)),
-            Err(e) => Err(e),
+    }
+
+    /// Return the path associated with `key`, allocating a new slot in
+    /// the underlying `FileTree` the first time `key` is seen. Looking up
+    /// the same key again always returns the same path.
+    ///
+    /// # Errors
+    ///
+    /// If `key` hasn't been seen before, this may need to create a new
+    /// subdirectory; any errors from that are returned here.
+    pub fn get(&mut self, key: String) -> Result<PathBuf> {
+        if let Some(path) = self.files.get(&key) {
+            return Ok(path.clone());
         }
+        let path = self.file_tree.get_new_file()?;
+        self.files.insert(key, path.clone());
+        Ok(path)
     }
 
     /// Return the root path for the file tree
     pub fn get_root(&self) -> PathBuf {
-        match self.tmp_dir {
-            Some(ref p) => p.path().to_path_buf(),
-            None => self.persistent_dir.as_ref().unwrap().to_path_buf(),
-        }
+        self.file_tree.get_root()
+    }
+
+    /// Return a copy of the key → path map, suitable for passing to
+    /// [`KeyedFileTree::from_existing`] to reconstruct this tree later.
+    pub fn get_existing_files(&self) -> HashMap<String, PathBuf> {
+        self.files.clone()
+    }
+
+    /// Returns an iterator over the keys that have been allocated a slot
+    /// so far.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.files.keys()
+    }
+
+    /// Returns an iterator over the key → path pairs that have been
+    /// allocated so far.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &PathBuf)> {
+        self.files.iter()
     }
 }
+
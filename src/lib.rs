@@ -5,4 +5,6 @@
 
 mod file_tree;
 
-pub use crate::file_tree::{FileTree, KeyedFileTree};
+pub use crate::file_tree::{
+    FileTree, FileTreeBuilder, FsBackend, FsWriteFile, KeyedFileTree, StdFsBackend,
+};
@@ -1,8 +1,11 @@
+use std::collections::{HashMap, HashSet};
 use std::env::temp_dir;
-use std::fs::File;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-use file_tree::FileTree;
+use file_tree::{FileTree, FileTreeBuilder, FsBackend, FsWriteFile};
 
 #[test]
 fn basic() {
@@ -67,3 +70,505 @@ fn from_existing() {
     assert_eq!(file_path.file_name().unwrap(), "000000000000");
     assert_eq!(file2.file_name().unwrap(), "000000000001");
 }
+
+#[test]
+fn builder_custom_layout() {
+    let mut ft = FileTreeBuilder::new()
+        .fanout(10)
+        .depth(2)
+        .prefix("data_")
+        .suffix(".bin")
+        .build(false)
+        .unwrap();
+
+    let path = ft.get_new_file().unwrap();
+    assert_eq!(path.file_name().unwrap(), "data_000.bin");
+    assert!(path.ends_with(PathBuf::from("0/0/data_000.bin")));
+
+    for _ in 0..9 {
+        ft.get_new_file().unwrap();
+    }
+    let path = ft.get_new_file().unwrap();
+    assert_eq!(path.file_name().unwrap(), "data_010.bin");
+    assert!(path.ends_with(PathBuf::from("0/1/data_010.bin")));
+}
+
+#[test]
+fn builder_custom_layout_non_power_of_ten_fanout() {
+    // A fanout that isn't an exact power of 10 still needs to pack exactly
+    // `fanout` entries per directory, not `10^segment_width`.
+    let mut ft = FileTreeBuilder::new().fanout(12).depth(1).build(false).unwrap();
+
+    for i in 0..12 {
+        let path = ft.get_new_file().unwrap();
+        assert!(
+            path.ends_with(PathBuf::from(format!("00/00{:02}", i))),
+            "slot {i} landed at {path:?}, expected it under directory 00"
+        );
+    }
+
+    let path = ft.get_new_file().unwrap();
+    assert!(path.ends_with(PathBuf::from("01/0100")));
+}
+
+#[test]
+fn builder_rejects_fanout_below_two() {
+    assert!(FileTreeBuilder::new().fanout(0).build(false).is_err());
+    assert!(FileTreeBuilder::new().fanout(1).build(false).is_err());
+}
+
+#[test]
+fn builder_defaults_match_new() {
+    let mut ft = FileTreeBuilder::new().build(false).unwrap();
+    let path = ft.get_new_file().unwrap();
+    assert_eq!(path.file_name().unwrap(), "000000000000");
+    assert!(path.ends_with(PathBuf::from("000/000/000/000000000000")));
+}
+
+#[test]
+fn write_new_file_is_readable() {
+    let mut ft = FileTree::new(false).unwrap();
+    let path = ft.write_new_file(b"hello world").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"hello world");
+
+    // no stray temp file left behind in the leaf directory
+    let siblings: Vec<_> = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(siblings, vec![path.file_name().unwrap().to_owned()]);
+}
+
+#[test]
+fn write_new_file_with_cleans_up_on_error() {
+    let mut ft = FileTree::new(false).unwrap();
+    let err = ft.write_new_file_with(|_file| Err(std::io::Error::other("boom")));
+    assert!(err.is_err());
+
+    let leaf_dir = ft.get_root().join("000/000/000");
+    let leftovers: Vec<_> = fs::read_dir(leaf_dir).unwrap().collect();
+    assert!(leftovers.is_empty());
+}
+
+/// An [`FsBackend`] that passes every operation through to `std::fs`
+/// except `rename`, which always fails, to exercise cleanup when the
+/// final rename step of an atomic write fails.
+#[derive(Debug, Default)]
+struct RenameFailingBackend;
+
+impl FsBackend for RenameFailingBackend {
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> std::io::Result<()> {
+        Err(std::io::Error::other("rename always fails"))
+    }
+
+    fn read_dir_names(&self, dir: &Path) -> Vec<(String, bool)> {
+        fs::read_dir(dir)
+            .map(|rd| {
+                rd.filter_map(|e| e.ok())
+                    .map(|e| {
+                        let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                        (e.file_name().to_string_lossy().into_owned(), is_dir)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn copy_file(&self, src: &Path, dest: &Path) -> std::io::Result<()> {
+        fs::copy(src, dest).map(|_| ())
+    }
+
+    fn create_file(&self, path: &Path) -> std::io::Result<Box<dyn FsWriteFile>> {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+#[test]
+fn write_new_file_with_cleans_up_tmp_file_on_rename_error() {
+    let mut ft =
+        FileTree::new_with_backend(Arc::new(RenameFailingBackend), temp_dir(), false).unwrap();
+
+    let err = ft.write_new_file(b"x");
+    assert!(err.is_err());
+
+    let leaf_dir = ft.get_root().join("000/000/000");
+    let leftovers: Vec<_> = fs::read_dir(leaf_dir).unwrap().collect();
+    assert!(leftovers.is_empty(), "orphan temp file left behind: {leftovers:?}");
+}
+
+#[test]
+fn resume_from_existing_continues_past_last_slot() {
+    let mut ft = FileTree::new(true).unwrap();
+    for _ in 0..1001 {
+        ft.write_new_file(b"x").unwrap();
+    }
+    let base = ft.get_root();
+    drop(ft);
+
+    let mut resumed = FileTree::resume_from_existing(base).unwrap();
+    let next = resumed.get_new_file().unwrap();
+    assert_eq!(next.file_name().unwrap(), "000000001001");
+    assert!(next.ends_with(PathBuf::from("000/000/001/000000001001")));
+}
+
+#[test]
+fn resume_from_existing_empty_tree_starts_at_zero() {
+    let ft = FileTree::new(true).unwrap();
+    let base = ft.get_root();
+    drop(ft);
+
+    let mut resumed = FileTree::resume_from_existing(base).unwrap();
+    assert_eq!(
+        resumed.get_new_file().unwrap().file_name().unwrap(),
+        "000000000000"
+    );
+}
+
+#[test]
+fn builder_resume_from_existing_respects_custom_layout() {
+    let builder = || {
+        FileTreeBuilder::new()
+            .fanout(1000)
+            .depth(1)
+            .prefix("data_")
+            .suffix(".bin")
+    };
+
+    let mut ft = builder().build(true).unwrap();
+    for _ in 0..5 {
+        ft.write_new_file(b"x").unwrap();
+    }
+    let base = ft.get_root();
+    drop(ft);
+
+    let mut resumed = builder().resume_from_existing(base).unwrap();
+    let next = resumed.get_new_file().unwrap();
+    assert_eq!(next.file_name().unwrap(), "data_000005.bin");
+    assert!(next.ends_with(PathBuf::from("000/data_000005.bin")));
+}
+
+#[test]
+fn resume_from_existing_survives_pruned_intermediate_branch() {
+    let builder = || FileTreeBuilder::new().fanout(10).depth(2);
+
+    let mut ft = builder().build(true).unwrap();
+    for _ in 0..105 {
+        ft.write_new_file(b"x").unwrap();
+    }
+    let base = ft.get_root();
+    drop(ft);
+
+    // Simulate deleting the 5 files under branch "1/0" and pruning the
+    // now-empty leaf directory, leaving "1/" itself present but with no
+    // numbered subdirectory underneath.
+    let pruned = base.join("1").join("0");
+    fs::remove_dir_all(&pruned).unwrap();
+    assert!(base.join("1").exists());
+    assert!(!pruned.exists());
+
+    let mut resumed = builder().resume_from_existing(base).unwrap();
+    let next = resumed.get_new_file().unwrap();
+    // Branch "0" was fully used (100 slots: 0/0/0..0/9/9), so the lowest
+    // safe resume point is the start of branch "1" (counter 100), not a
+    // reset all the way back to 0.
+    assert_eq!(next.file_name().unwrap(), "100");
+    assert!(next.ends_with(PathBuf::from("1/0/100")));
+}
+
+#[test]
+fn builder_from_existing_respects_custom_layout() {
+    let builder = || FileTreeBuilder::new().fanout(10).depth(1).suffix(".bin");
+
+    let mut ft = builder().build(true).unwrap();
+    let file_path = ft.write_new_file(b"x").unwrap();
+    let base = ft.get_root();
+    drop(ft);
+
+    let mut ft2 = builder().from_existing(base).unwrap();
+    let file2 = ft2.get_new_file().unwrap();
+    assert_eq!(file_path.file_name().unwrap(), "00.bin");
+    assert_eq!(file2.file_name().unwrap(), "01.bin");
+}
+
+#[test]
+fn iter_yields_every_slot_in_counter_order() {
+    let mut ft = FileTree::new(false).unwrap();
+    let mut expected = Vec::new();
+    for _ in 0..1001 {
+        expected.push(ft.write_new_file(b"x").unwrap());
+    }
+
+    let found: Vec<_> = ft.iter().collect();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn iter_on_empty_tree_yields_nothing() {
+    let ft = FileTree::new(false).unwrap();
+    assert_eq!(ft.iter().count(), 0);
+}
+
+#[test]
+fn persist_moves_contents_and_keeps_counter() {
+    let mut ft = FileTree::new(false).unwrap();
+    let original = ft.write_new_file(b"keep me").unwrap();
+    let source = ft.get_root();
+
+    let dest = temp_dir().join(format!("file-tree-persist-test-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dest);
+
+    let mut persisted = ft.persist(dest.clone()).unwrap();
+    assert!(!source.exists());
+    assert!(dest.exists());
+    assert_eq!(
+        fs::read(dest.join(original.strip_prefix(&source).unwrap())).unwrap(),
+        b"keep me"
+    );
+
+    let next = persisted.get_new_file().unwrap();
+    assert_eq!(next.file_name().unwrap(), "000000000001");
+
+    // dropping the persisted tree must not delete it
+    drop(persisted);
+    assert!(dest.exists());
+
+    fs::remove_dir_all(&dest).unwrap();
+}
+
+/// A writable handle into [`InMemoryFsBackend`]'s in-memory file map,
+/// buffering bytes until `sync_all` commits them under `path`.
+struct InMemoryWriteFile {
+    path: PathBuf,
+    buf: Vec<u8>,
+    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+}
+
+impl Write for InMemoryWriteFile {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FsWriteFile for InMemoryWriteFile {
+    fn sync_all(&self) -> std::io::Result<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(self.path.clone(), self.buf.clone());
+        Ok(())
+    }
+}
+
+/// A minimal in-memory [`FsBackend`] that tracks created directories and
+/// written file contents, to exercise `get_new_file`'s and
+/// `write_new_file`'s allocation and write logic without touching the
+/// real filesystem.
+#[derive(Debug, Default)]
+struct InMemoryFsBackend {
+    dirs: Mutex<HashSet<PathBuf>>,
+    files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+}
+
+impl FsBackend for InMemoryFsBackend {
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        self.dirs.lock().unwrap().insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.dirs.lock().unwrap().contains(path) || self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(data) = files.remove(from) {
+            files.insert(to.to_path_buf(), data);
+        }
+        Ok(())
+    }
+
+    fn read_dir_names(&self, _dir: &Path) -> Vec<(String, bool)> {
+        Vec::new()
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        self.files.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        self.dirs.lock().unwrap().retain(|d| !d.starts_with(path));
+        self.files.lock().unwrap().retain(|f, _| !f.starts_with(path));
+        Ok(())
+    }
+
+    fn copy_file(&self, _src: &Path, _dest: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path) -> std::io::Result<Box<dyn FsWriteFile>> {
+        Ok(Box::new(InMemoryWriteFile {
+            path: path.to_path_buf(),
+            buf: Vec::new(),
+            files: self.files.clone(),
+        }))
+    }
+}
+
+#[test]
+fn new_with_backend_allocates_without_touching_real_fs() {
+    let backend = Arc::new(InMemoryFsBackend::default());
+    let root = PathBuf::from("/fake/root");
+    let mut ft = FileTree::new_with_backend(backend, root.clone(), true).unwrap();
+
+    let path = ft.get_new_file().unwrap();
+    assert_eq!(path.file_name().unwrap(), "000000000000");
+    assert!(path.ends_with(PathBuf::from("000/000/000/000000000000")));
+    assert!(!root.exists());
+}
+
+#[test]
+fn new_with_backend_non_persistent_never_touches_real_fs() {
+    let backend = Arc::new(InMemoryFsBackend::default());
+    let root = PathBuf::from("/fake/root");
+    let ft = FileTree::new_with_backend(backend, root.clone(), false).unwrap();
+
+    let fake_root = ft.get_root();
+    assert!(fake_root.starts_with(&root));
+    drop(ft);
+
+    // Nothing here ever touched the real filesystem, persistent or not.
+    assert!(!root.exists());
+    assert!(!fake_root.exists());
+}
+
+#[test]
+fn new_with_backend_write_new_file_never_touches_real_fs() {
+    let backend = Arc::new(InMemoryFsBackend::default());
+    let root = PathBuf::from("/fake/root");
+    let mut ft = FileTree::new_with_backend(backend.clone(), root.clone(), true).unwrap();
+
+    let path = ft.write_new_file(b"hello").unwrap();
+    assert!(!root.exists());
+    assert_eq!(backend.files.lock().unwrap().get(&path).unwrap(), b"hello");
+}
+
+/// A minimal in-memory [`FsBackend`] that can be pre-seeded with
+/// directories and files, to exercise `resume_from_existing_with_backend`
+/// against a fake directory listing instead of the real filesystem.
+#[derive(Debug, Default)]
+struct SeededFsBackend {
+    // path -> is_dir
+    entries: Mutex<HashMap<PathBuf, bool>>,
+}
+
+impl SeededFsBackend {
+    fn seed_dir(&self, path: &Path) {
+        self.entries.lock().unwrap().insert(path.to_path_buf(), true);
+    }
+
+    fn seed_file(&self, path: &Path) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), false);
+    }
+}
+
+impl FsBackend for SeededFsBackend {
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        self.entries.lock().unwrap().insert(path.to_path_buf(), true);
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().contains_key(path)
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn read_dir_names(&self, dir: &Path) -> Vec<(String, bool)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir))
+            .filter_map(|(path, is_dir)| {
+                Some((path.file_name()?.to_string_lossy().into_owned(), *is_dir))
+            })
+            .collect()
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        self.entries.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+
+    fn copy_file(&self, _src: &Path, _dest: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path) -> std::io::Result<Box<dyn FsWriteFile>> {
+        Ok(Box::new(InMemoryWriteFile {
+            path: path.to_path_buf(),
+            buf: Vec::new(),
+            files: Arc::new(Mutex::new(HashMap::new())),
+        }))
+    }
+}
+
+#[test]
+fn resume_from_existing_with_backend_reads_through_the_backend() {
+    let backend = Arc::new(SeededFsBackend::default());
+    let root = PathBuf::from("/fake/root");
+
+    // Seed a fanout(10).depth(1) tree containing counters 0..=3, without
+    // ever touching the real filesystem.
+    backend.seed_dir(&root);
+    backend.seed_dir(&root.join("0"));
+    for name in ["00", "01", "02", "03"] {
+        backend.seed_file(&root.join("0").join(name));
+    }
+
+    let mut resumed = FileTreeBuilder::new()
+        .fanout(10)
+        .depth(1)
+        .resume_from_existing_with_backend(backend.clone(), root.clone())
+        .unwrap();
+
+    let next = resumed.get_new_file().unwrap();
+    assert_eq!(next.file_name().unwrap(), "04");
+    assert!(next.ends_with(PathBuf::from("0/04")));
+    assert!(!root.exists());
+}
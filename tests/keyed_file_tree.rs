@@ -78,3 +78,26 @@ fn from_existing() {
     assert_eq!(file_path, file2);
     assert_ne!(file2, file3);
 }
+
+#[test]
+fn keys_and_entries() {
+    let mut ft = KeyedFileTree::new(false).unwrap();
+    let path1 = ft.get(String::from("key1")).unwrap();
+    let path2 = ft.get(String::from("key2")).unwrap();
+
+    let mut keys: Vec<_> = ft.keys().cloned().collect();
+    keys.sort();
+    assert_eq!(keys, vec![String::from("key1"), String::from("key2")]);
+
+    let mut entries: Vec<_> = ft
+        .entries()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    entries.sort();
+    let mut expected = vec![
+        (String::from("key1"), path1),
+        (String::from("key2"), path2),
+    ];
+    expected.sort();
+    assert_eq!(entries, expected);
+}